@@ -0,0 +1,211 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Set from `cancel_query` and checked by `client::send_script` while it
+/// waits on a reply, and by `QueryLimiter::acquire` while it waits for a slot.
+#[derive(Clone, Debug, Default)]
+pub struct CancelFlag(Arc<FlagState>);
+
+#[derive(Debug, Default)]
+struct FlagState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelFlag {
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once `cancel` has been called.
+    pub async fn cancelled(&self) {
+        let notified = self.0.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// A query `QueryRegistry` is keeping track of.
+struct Running {
+    token: Option<String>,
+    flag: CancelFlag,
+    /// False while the query is still waiting on the `QueryLimiter`.
+    sent: bool,
+}
+
+/// Every running query, along with the token the frontend started it with
+/// if it gave one.
+#[derive(Default)]
+pub struct QueryRegistry {
+    running: Mutex<Vec<Running>>,
+    shutting_down: AtomicBool,
+}
+
+impl QueryRegistry {
+    /// Track a new query. Once shutdown has begun the returned flag is
+    /// already cancelled.
+    pub fn register(&self, token: Option<String>) -> CancelFlag {
+        let flag = CancelFlag::default();
+        if self.shutting_down.load(Ordering::SeqCst) {
+            flag.cancel();
+        }
+        self.running.lock().unwrap().push(Running {
+            token,
+            flag: flag.clone(),
+            sent: false,
+        });
+        flag
+    }
+
+    /// Note that a query got past the `QueryLimiter` and is being sent.
+    pub fn mark_sent(&self, flag: &CancelFlag) {
+        for running in self.running.lock().unwrap().iter_mut() {
+            if Arc::ptr_eq(&running.flag.0, &flag.0) {
+                running.sent = true;
+            }
+        }
+    }
+
+    /// Forget a query once it is done.
+    pub fn finish(&self, flag: &CancelFlag) {
+        self.running
+            .lock()
+            .unwrap()
+            .retain(|running| !Arc::ptr_eq(&running.flag.0, &flag.0));
+    }
+
+    /// Returns false when no query is running under `token`.
+    pub fn cancel(&self, token: &str) -> bool {
+        let running = self.running.lock().unwrap();
+        let mut found = false;
+        for running in running.iter().filter(|r| r.token.as_deref() == Some(token)) {
+            running.flag.cancel();
+            found = true;
+        }
+        found
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.running.lock().unwrap().is_empty()
+    }
+
+    /// Returns true the first time it's called, so shutdown only runs once.
+    pub fn begin_shutdown(&self) -> bool {
+        !self.shutting_down.swap(true, Ordering::SeqCst)
+    }
+
+    /// Cancel queries that haven't been sent yet, give the ones already sent
+    /// up to `grace` to finish, then cancel whatever is left. Any sent script
+    /// may be writing, so those aren't cut off before the grace period is up.
+    /// This blocks, so don't call it from the main thread.
+    pub fn shutdown(&self, grace: Duration) {
+        for running in self.running.lock().unwrap().iter() {
+            if !running.sent {
+                running.flag.cancel();
+            }
+        }
+        let deadline = Instant::now() + grace;
+        while !self.is_idle() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        for running in self.running.lock().unwrap().iter() {
+            running.flag.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_only_affects_its_own_token() {
+        let queries = QueryRegistry::default();
+        let a = queries.register(Some("a".to_string()));
+        let b = queries.register(Some("b".to_string()));
+        let anonymous = queries.register(None);
+        assert!(queries.cancel("a"));
+        assert!(a.is_cancelled());
+        assert!(!b.is_cancelled());
+        assert!(!anonymous.is_cancelled());
+    }
+
+    #[test]
+    fn cancel_reaches_every_query_sharing_a_token() {
+        let queries = QueryRegistry::default();
+        let first = queries.register(Some("a".to_string()));
+        let second = queries.register(Some("a".to_string()));
+        assert!(queries.cancel("a"));
+        assert!(first.is_cancelled());
+        assert!(second.is_cancelled());
+    }
+
+    #[test]
+    fn cancel_returns_false_for_unknown_token() {
+        let queries = QueryRegistry::default();
+        queries.register(Some("a".to_string()));
+        assert!(!queries.cancel("b"));
+    }
+
+    #[test]
+    fn finish_removes_only_its_own_flag() {
+        let queries = QueryRegistry::default();
+        let first = queries.register(Some("a".to_string()));
+        let second = queries.register(Some("a".to_string()));
+        queries.finish(&first);
+        assert!(queries.cancel("a"));
+        assert!(!first.is_cancelled());
+        assert!(second.is_cancelled());
+        queries.finish(&second);
+        assert!(!queries.cancel("a"));
+    }
+
+    #[test]
+    fn shutdown_cancels_queries_still_running_after_grace() {
+        let queries = QueryRegistry::default();
+        let flag = queries.register(None);
+        queries.mark_sent(&flag);
+        queries.shutdown(Duration::from_millis(100));
+        assert!(flag.is_cancelled());
+    }
+
+    #[test]
+    fn shutdown_cancels_unsent_queries_right_away() {
+        let queries = Arc::new(QueryRegistry::default());
+        let queued = queries.register(None);
+        let sent = queries.register(None);
+        queries.mark_sent(&sent);
+        let start = Instant::now();
+        let shutdown = {
+            let queries = queries.clone();
+            thread::spawn(move || queries.shutdown(Duration::from_secs(10)))
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert!(queued.is_cancelled());
+        assert!(!sent.is_cancelled());
+        queries.finish(&queued);
+        queries.finish(&sent);
+        shutdown.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn queries_registered_during_shutdown_start_cancelled() {
+        let queries = QueryRegistry::default();
+        assert!(queries.begin_shutdown());
+        assert!(!queries.begin_shutdown());
+        assert!(queries.register(None).is_cancelled());
+    }
+}
@@ -0,0 +1,367 @@
+use crate::cancel::CancelFlag;
+use crate::config::ServerConfig;
+use crate::error::PadError;
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// How often a pending request checks whether it has been cancelled.
+const CANCEL_POLL_MS: u64 = 100;
+
+/// How much of a reply that isn't UTF-8 is shown in `PadError::Decode`.
+const DECODE_PREVIEW_BYTES: usize = 64;
+
+/// `check_server` never waits longer than this, so an offline server is
+/// reported quickly.
+const PROBE_TIMEOUT_MS: u64 = 2_000;
+
+/// How long to wait after losing the connection for zmq to start
+/// reconnecting before deciding the reply was too large.
+const RECONNECT_WAIT_MS: u64 = 100;
+
+/// Numbers the inproc endpoints used by `watch_connection` so each socket
+/// gets its own.
+static NEXT_MONITOR: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Serialize)]
+pub struct ServerStatus {
+    pub reachable: bool,
+    /// The server doesn't report a version over zmq yet, so this is always `None`.
+    pub version: Option<String>,
+    pub latency_ms: u64,
+}
+
+/// Send `script` to the server described by `config` and wait for its reply,
+/// giving up early with `PadError::Cancelled` once `cancel` is set.
+///
+/// A fresh REQ socket is used for every call since a REQ socket that timed
+/// out waiting for a reply can't be used to send again.
+pub fn send_script(
+    ctx: &zmq::Context,
+    config: &ServerConfig,
+    script: &str,
+    cancel: &CancelFlag,
+) -> Result<String, PadError> {
+    let endpoint = config.endpoint();
+    log::debug!("sending {} byte script to {}", script.len(), endpoint);
+    log::trace!("script: {}", script);
+    if cancel.is_cancelled() {
+        return Err(PadError::Cancelled);
+    }
+    let socket = ctx.socket(zmq::REQ)?;
+    socket.set_linger(0)?;
+    socket.set_sndtimeo(i32::try_from(config.timeout_ms).unwrap_or(i32::MAX))?;
+    socket.set_maxmsgsize(i64::try_from(config.max_reply_bytes).unwrap_or(i64::MAX))?;
+    let monitor = watch_connection(ctx, &socket)?;
+    socket.connect(&endpoint)?;
+    socket.send(script, 0).map_err(timeout_or_network)?;
+    wait_for_reply(&socket, &monitor, config, cancel)?;
+    let reply = socket
+        .recv_string(zmq::DONTWAIT)
+        .map_err(timeout_or_network)?
+        .map_err(|bytes| decode_error(&bytes));
+    match &reply {
+        Ok(text) => {
+            log::debug!("received {} byte reply from {}", text.len(), endpoint);
+            log::trace!("reply: {}", text);
+        }
+        Err(e) => log::debug!("request to {} failed: {}", endpoint, e),
+    }
+    reply
+}
+
+/// Fail with `PadError::PayloadTooLarge` when `script` is over
+/// `ServerConfig::max_script_bytes`. `send_script` doesn't check this itself;
+/// `run_wander` does before the script is queued.
+pub fn check_script_size(config: &ServerConfig, script: &str) -> Result<(), PadError> {
+    if script.len() > config.max_script_bytes {
+        return Err(PadError::PayloadTooLarge {
+            size: script.len(),
+            limit: config.max_script_bytes,
+        });
+    }
+    Ok(())
+}
+
+/// Describe a reply that isn't UTF-8, with a lossy preview of how it starts.
+fn decode_error(bytes: &[u8]) -> PadError {
+    let preview = String::from_utf8_lossy(&bytes[..bytes.len().min(DECODE_PREVIEW_BYTES)]);
+    PadError::Decode(format!(
+        "{} byte reply was not valid UTF-8, starting with {:?}",
+        bytes.len(),
+        preview
+    ))
+}
+
+/// A PAIR socket that receives an event each time `socket` loses its
+/// connection or retries connecting. zmq drops the connection without
+/// reconnecting when a reply is over the socket's max message size, and this
+/// is the only way to find out.
+fn watch_connection(ctx: &zmq::Context, socket: &zmq::Socket) -> Result<zmq::Socket, PadError> {
+    let endpoint = format!(
+        "inproc://ligature-pad-monitor-{}",
+        NEXT_MONITOR.fetch_add(1, Ordering::SeqCst)
+    );
+    let events =
+        zmq::SocketEvent::DISCONNECTED.to_raw() | zmq::SocketEvent::CONNECT_RETRIED.to_raw();
+    socket.monitor(&endpoint, i32::from(events))?;
+    let monitor = ctx.socket(zmq::PAIR)?;
+    monitor.set_linger(0)?;
+    monitor.connect(&endpoint)?;
+    Ok(monitor)
+}
+
+/// Read one event sent by the socket `watch_connection` set up.
+fn read_event(monitor: &zmq::Socket) -> Result<zmq::SocketEvent, PadError> {
+    let event = monitor.recv_bytes(0)?;
+    // The second frame names the endpoint, which is always the server's.
+    if monitor.get_rcvmore()? {
+        monitor.recv_bytes(0)?;
+    }
+    Ok(zmq::SocketEvent::from_raw(u16::from_ne_bytes([
+        event[0], event[1],
+    ])))
+}
+
+/// Block until `socket` has a reply to read, the timeout passes, or the
+/// request is cancelled. Losing the connection without zmq reconnecting
+/// means the reply was over `ServerConfig::max_reply_bytes`.
+fn wait_for_reply(
+    socket: &zmq::Socket,
+    monitor: &zmq::Socket,
+    config: &ServerConfig,
+    cancel: &CancelFlag,
+) -> Result<(), PadError> {
+    let deadline = Instant::now() + Duration::from_millis(config.timeout_ms);
+    let mut disconnected: Option<Instant> = None;
+    loop {
+        if cancel.is_cancelled() {
+            return Err(PadError::Cancelled);
+        }
+        let now = Instant::now();
+        let remaining = deadline.saturating_duration_since(now);
+        if remaining.is_zero() {
+            return Err(PadError::Timeout);
+        }
+        let mut wait = remaining.min(Duration::from_millis(CANCEL_POLL_MS));
+        if let Some(at) = disconnected {
+            let retry_by = at + Duration::from_millis(RECONNECT_WAIT_MS);
+            if now >= retry_by {
+                return Err(PadError::ReplyTooLarge {
+                    limit: config.max_reply_bytes,
+                });
+            }
+            wait = wait.min(retry_by - now);
+        }
+        let mut items = [
+            socket.as_poll_item(zmq::POLLIN),
+            monitor.as_poll_item(zmq::POLLIN),
+        ];
+        if zmq::poll(&mut items, wait.as_millis() as i64)? == 0 {
+            continue;
+        }
+        if items[0].is_readable() {
+            return Ok(());
+        }
+        if items[1].is_readable() {
+            match read_event(monitor)? {
+                zmq::SocketEvent::DISCONNECTED => disconnected = Some(Instant::now()),
+                // The server went away but zmq is reconnecting, so keep
+                // waiting; the request is lost and this ends in a timeout.
+                _ => disconnected = None,
+            }
+        }
+    }
+}
+
+fn timeout_or_network(e: zmq::Error) -> PadError {
+    match e {
+        zmq::Error::EAGAIN => PadError::Timeout,
+        e => e.into(),
+    }
+}
+
+/// Send an empty script and time how long the server takes to answer.
+pub fn probe(ctx: &zmq::Context, config: &ServerConfig) -> Result<ServerStatus, PadError> {
+    let config = ServerConfig {
+        timeout_ms: config.timeout_ms.min(PROBE_TIMEOUT_MS),
+        ..config.clone()
+    };
+    let start = Instant::now();
+    let reachable = match send_script(ctx, &config, "", &CancelFlag::default()) {
+        Ok(_) | Err(PadError::Decode(_)) | Err(PadError::ReplyTooLarge { .. }) => true,
+        Err(PadError::Timeout) => false,
+        Err(e) => return Err(e),
+    };
+    Ok(ServerStatus {
+        reachable,
+        version: None,
+        latency_ms: start.elapsed().as_millis() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn config(port: u16) -> ServerConfig {
+        ServerConfig {
+            port,
+            timeout_ms: 500,
+            ..ServerConfig::default()
+        }
+    }
+
+    /// Bind a REP socket on a free port. When `reply` is given the server
+    /// answers one request with it; otherwise it reads the request and never
+    /// answers. The join handle yields the request it received.
+    fn mock_server(
+        ctx: &zmq::Context,
+        reply: Option<&'static [u8]>,
+    ) -> (ServerConfig, thread::JoinHandle<Vec<u8>>) {
+        let socket = ctx.socket(zmq::REP).unwrap();
+        socket.bind("tcp://127.0.0.1:*").unwrap();
+        let endpoint = socket.get_last_endpoint().unwrap().unwrap();
+        let port = endpoint.rsplit(':').next().unwrap().parse().unwrap();
+        let handle = thread::spawn(move || {
+            let request = socket.recv_bytes(0).unwrap();
+            if let Some(reply) = reply {
+                socket.send(reply, 0).unwrap();
+            }
+            request
+        });
+        (config(port), handle)
+    }
+
+    /// A port nothing is listening on.
+    fn closed_port(ctx: &zmq::Context) -> u16 {
+        let socket = ctx.socket(zmq::REP).unwrap();
+        socket.bind("tcp://127.0.0.1:*").unwrap();
+        let endpoint = socket.get_last_endpoint().unwrap().unwrap();
+        endpoint.rsplit(':').next().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn send_script_returns_reply() {
+        let ctx = zmq::Context::new();
+        let (config, server) = mock_server(&ctx, Some(b"{a b c}"));
+        let reply = send_script(&ctx, &config, "query", &CancelFlag::default()).unwrap();
+        assert_eq!(reply, "{a b c}");
+        assert_eq!(server.join().unwrap(), b"query");
+    }
+
+    #[test]
+    fn send_script_reports_invalid_utf8_reply() {
+        let ctx = zmq::Context::new();
+        let (config, server) = mock_server(&ctx, Some(b"ok\xff\xfe"));
+        let result = send_script(&ctx, &config, "query", &CancelFlag::default());
+        match result {
+            Err(PadError::Decode(message)) => {
+                assert!(message.contains("4 byte reply"), "{}", message);
+                assert!(message.contains("ok\u{fffd}\u{fffd}"), "{}", message);
+            }
+            other => panic!("expected a decode error, got {:?}", other),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn send_script_times_out_when_server_never_replies() {
+        let ctx = zmq::Context::new();
+        let (config, server) = mock_server(&ctx, None);
+        let result = send_script(&ctx, &config, "query", &CancelFlag::default());
+        assert!(matches!(result, Err(PadError::Timeout)));
+        assert_eq!(server.join().unwrap(), b"query");
+    }
+
+    #[test]
+    fn send_script_times_out_when_nothing_is_listening() {
+        let ctx = zmq::Context::new();
+        let config = config(closed_port(&ctx));
+        let result = send_script(&ctx, &config, "query", &CancelFlag::default());
+        assert!(matches!(result, Err(PadError::Timeout)));
+    }
+
+    #[test]
+    fn send_script_stops_when_cancelled() {
+        let ctx = zmq::Context::new();
+        let (config, server) = mock_server(&ctx, None);
+        let config = ServerConfig {
+            timeout_ms: 10_000,
+            ..config
+        };
+        let cancel = CancelFlag::default();
+        let canceller = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            canceller.cancel();
+        });
+        let start = Instant::now();
+        let result = send_script(&ctx, &config, "query", &cancel);
+        assert!(matches!(result, Err(PadError::Cancelled)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(server.join().unwrap(), b"query");
+    }
+
+    #[test]
+    fn check_script_size_rejects_oversized_script() {
+        let config = ServerConfig {
+            max_script_bytes: 4,
+            ..ServerConfig::default()
+        };
+        assert!(check_script_size(&config, "four").is_ok());
+        assert!(matches!(
+            check_script_size(&config, "query"),
+            Err(PadError::PayloadTooLarge { size: 5, limit: 4 })
+        ));
+    }
+
+    #[test]
+    fn send_script_drops_oversized_reply() {
+        let ctx = zmq::Context::new();
+        let (config, server) = mock_server(&ctx, Some(&[b'a'; 4096]));
+        // The limit covers zmq's handshake too, so it can't be tiny.
+        let config = ServerConfig {
+            timeout_ms: 10_000,
+            max_reply_bytes: 1024,
+            ..config
+        };
+        let start = Instant::now();
+        let result = send_script(&ctx, &config, "query", &CancelFlag::default());
+        assert!(matches!(
+            result,
+            Err(PadError::ReplyTooLarge { limit: 1024 })
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn send_script_rejects_invalid_endpoint() {
+        let ctx = zmq::Context::new();
+        let config = ServerConfig {
+            scheme: "nope".to_string(),
+            ..config(4200)
+        };
+        let result = send_script(&ctx, &config, "query", &CancelFlag::default());
+        assert!(matches!(result, Err(PadError::Network(_))));
+    }
+
+    #[test]
+    fn probe_reports_reachable_server() {
+        let ctx = zmq::Context::new();
+        let (config, server) = mock_server(&ctx, Some(b""));
+        let status = probe(&ctx, &config).unwrap();
+        assert!(status.reachable);
+        assert_eq!(server.join().unwrap(), b"");
+    }
+
+    #[test]
+    fn probe_reports_unreachable_server() {
+        let ctx = zmq::Context::new();
+        let config = config(closed_port(&ctx));
+        let status = probe(&ctx, &config).unwrap();
+        assert!(!status.reachable);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::error::PadError;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Where the Ligature server that `run_wander` talks to lives.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub scheme: String,
+    /// How long to wait on the server before giving up with `PadError::Timeout`.
+    /// A `timeout_ms` passed to `run_wander` takes precedence over this.
+    pub timeout_ms: u64,
+    /// How many `run_wander` calls may wait on the server at once; any more
+    /// queue until one finishes. Must be at least 1.
+    pub max_concurrent_queries: usize,
+    /// Scripts larger than this are rejected before they're sent.
+    pub max_script_bytes: usize,
+    /// Replies larger than this are dropped by zmq rather than read into
+    /// memory, failing with `PadError::ReplyTooLarge`. zmq applies it to its
+    /// handshake too, so it can't be set below a few hundred bytes.
+    pub max_reply_bytes: usize,
+    /// Emit a `command-timing` event after each server command.
+    pub debug_timing: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 4200,
+            scheme: "tcp".to_string(),
+            timeout_ms: 30_000,
+            max_concurrent_queries: 4,
+            max_script_bytes: 16 * 1024 * 1024,
+            max_reply_bytes: 64 * 1024 * 1024,
+            debug_timing: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The zmq endpoint string, e.g. `tcp://127.0.0.1:4200`.
+    pub fn endpoint(&self) -> String {
+        format!("{}://{}:{}", self.scheme, self.host, self.port)
+    }
+
+    /// Fail with `PadError::InvalidConfig` for settings that can't work, so
+    /// they are never saved.
+    pub fn validate(&self) -> Result<(), PadError> {
+        if self.max_concurrent_queries == 0 {
+            return Err(PadError::InvalidConfig(
+                "max_concurrent_queries must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The active `ServerConfig` along with the file it is persisted to.
+pub struct ConfigStore {
+    path: PathBuf,
+    config: Mutex<ServerConfig>,
+}
+
+impl ConfigStore {
+    /// Read the config saved at `path`, falling back to the default
+    /// when nothing has been saved yet or the file can't be parsed.
+    pub fn load(path: PathBuf) -> ConfigStore {
+        let config = load(&path);
+        ConfigStore {
+            path,
+            config: Mutex::new(config),
+        }
+    }
+
+    pub fn get(&self) -> ServerConfig {
+        self.config.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, config: ServerConfig) -> io::Result<()> {
+        save(&self.path, &config)?;
+        *self.config.lock().unwrap() = config;
+        Ok(())
+    }
+}
+
+/// Named server configs the user can switch between with `activate_profile`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerProfiles {
+    /// The profile last activated, or `None` once the active config has been
+    /// changed by other means or that profile was removed.
+    pub active: Option<String>,
+    pub profiles: BTreeMap<String, ServerConfig>,
+}
+
+/// The saved `ServerProfiles` along with the file they are persisted to.
+pub struct ProfileStore {
+    path: PathBuf,
+    profiles: Mutex<ServerProfiles>,
+}
+
+impl ProfileStore {
+    pub fn load(path: PathBuf) -> ProfileStore {
+        let profiles = load(&path);
+        ProfileStore {
+            path,
+            profiles: Mutex::new(profiles),
+        }
+    }
+
+    pub fn list(&self) -> ServerProfiles {
+        self.profiles.lock().unwrap().clone()
+    }
+
+    pub fn get(&self, name: &str) -> Result<ServerConfig, PadError> {
+        self.profiles
+            .lock()
+            .unwrap()
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| PadError::UnknownProfile(name.to_string()))
+    }
+
+    /// Save `config` under `name`, replacing any profile already using it.
+    /// Returns true when `name` is the active profile, so the active config
+    /// needs to be replaced with `config` too.
+    pub fn add(&self, name: String, config: ServerConfig) -> Result<bool, PadError> {
+        let mut active = false;
+        self.update(|profiles| {
+            active = profiles.active.as_ref() == Some(&name);
+            profiles.profiles.insert(name, config);
+            Ok(())
+        })?;
+        Ok(active)
+    }
+
+    pub fn remove(&self, name: &str) -> Result<(), PadError> {
+        self.update(|profiles| {
+            if profiles.profiles.remove(name).is_none() {
+                return Err(PadError::UnknownProfile(name.to_string()));
+            }
+            if profiles.active.as_deref() == Some(name) {
+                profiles.active = None;
+            }
+            Ok(())
+        })
+    }
+
+    /// Record which profile the active config came from.
+    pub fn set_active(&self, name: Option<String>) -> Result<(), PadError> {
+        self.update(|profiles| {
+            if let Some(name) = &name {
+                if !profiles.profiles.contains_key(name) {
+                    return Err(PadError::UnknownProfile(name.clone()));
+                }
+            }
+            profiles.active = name;
+            Ok(())
+        })
+    }
+
+    fn update(
+        &self,
+        f: impl FnOnce(&mut ServerProfiles) -> Result<(), PadError>,
+    ) -> Result<(), PadError> {
+        let mut profiles = self.profiles.lock().unwrap();
+        let mut updated = profiles.clone();
+        f(&mut updated)?;
+        if updated != *profiles {
+            save(&self.path, &updated)?;
+            *profiles = updated;
+        }
+        Ok(())
+    }
+}
+
+/// Read a JSON file, falling back to the default when it's missing or can't be parsed.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+            log::warn!("ignoring unreadable config {}: {}", path.display(), e);
+            T::default()
+        }),
+        Err(_) => T::default(),
+    }
+}
+
+/// Write `value` to `path` as JSON. It goes to a temporary file first and is
+/// then renamed into place, so a crash mid-write can't leave `path` truncated.
+pub fn save<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let text = serde_json::to_string_pretty(value)?;
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, text)?;
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn config_store_defaults_when_nothing_is_saved() {
+        let dir = TempDir::new();
+        let store = ConfigStore::load(dir.path("missing.json"));
+        assert_eq!(store.get(), ServerConfig::default());
+        assert_eq!(store.get().endpoint(), "tcp://127.0.0.1:4200");
+    }
+
+    #[test]
+    fn validate_rejects_zero_concurrent_queries() {
+        assert!(ServerConfig::default().validate().is_ok());
+        let config = ServerConfig {
+            max_concurrent_queries: 0,
+            ..ServerConfig::default()
+        };
+        assert!(matches!(config.validate(), Err(PadError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn config_store_persists_across_loads() {
+        let dir = TempDir::new();
+        let path = dir.path("server.json");
+        let config = ServerConfig {
+            host: "example.com".to_string(),
+            port: 4300,
+            ..ServerConfig::default()
+        };
+        ConfigStore::load(path.clone()).set(config.clone()).unwrap();
+        assert_eq!(ConfigStore::load(path.clone()).get(), config);
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn profile_store_add_and_remove() {
+        let dir = TempDir::new();
+        let path = dir.path("profiles.json");
+        let profiles = ProfileStore::load(path.clone());
+        profiles
+            .add("local".to_string(), ServerConfig::default())
+            .unwrap();
+        assert_eq!(
+            ProfileStore::load(path.clone()).get("local").unwrap(),
+            ServerConfig::default()
+        );
+        profiles.remove("local").unwrap();
+        assert!(ProfileStore::load(path).list().profiles.is_empty());
+    }
+
+    #[test]
+    fn profile_store_tracks_active_profile() {
+        let dir = TempDir::new();
+        let path = dir.path("active-profiles.json");
+        let profiles = ProfileStore::load(path.clone());
+        profiles
+            .add("local".to_string(), ServerConfig::default())
+            .unwrap();
+        profiles.set_active(Some("local".to_string())).unwrap();
+        assert_eq!(
+            ProfileStore::load(path.clone()).list().active.as_deref(),
+            Some("local")
+        );
+        profiles.remove("local").unwrap();
+        assert_eq!(ProfileStore::load(path).list().active, None);
+    }
+
+    #[test]
+    fn profile_store_reports_replacing_active_profile() {
+        let dir = TempDir::new();
+        let profiles = ProfileStore::load(dir.path("replaced-profiles.json"));
+        let staging = ServerConfig {
+            host: "staging.example.com".to_string(),
+            ..ServerConfig::default()
+        };
+        assert!(!profiles
+            .add("local".to_string(), ServerConfig::default())
+            .unwrap());
+        profiles.set_active(Some("local".to_string())).unwrap();
+        assert!(!profiles
+            .add("staging".to_string(), staging.clone())
+            .unwrap());
+        assert!(profiles.add("local".to_string(), staging.clone()).unwrap());
+        assert_eq!(profiles.get("local").unwrap(), staging);
+        assert_eq!(profiles.list().active.as_deref(), Some("local"));
+    }
+
+    #[test]
+    fn profile_store_rejects_unknown_profile() {
+        let dir = TempDir::new();
+        let profiles = ProfileStore::load(dir.path("empty-profiles.json"));
+        assert!(matches!(
+            profiles.get("staging"),
+            Err(PadError::UnknownProfile(_))
+        ));
+        assert!(matches!(
+            profiles.remove("staging"),
+            Err(PadError::UnknownProfile(_))
+        ));
+        assert!(matches!(
+            profiles.set_active(Some("staging".to_string())),
+            Err(PadError::UnknownProfile(_))
+        ));
+    }
+}
@@ -0,0 +1,67 @@
+use serde::Serialize;
+use std::fmt;
+use std::io;
+
+/// Errors returned to the frontend, where they show up as a rejected promise.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message")]
+pub enum PadError {
+    /// The zmq socket couldn't be created, connected, or used.
+    Network(String),
+    /// The server didn't answer within the configured timeout.
+    Timeout,
+    /// The request was stopped with `cancel_query` before the server replied.
+    Cancelled,
+    /// The server replied with something that isn't a UTF-8 string.
+    Decode(String),
+    /// Reading or writing a local file failed.
+    Io(String),
+    /// No saved server profile has this name.
+    UnknownProfile(String),
+    /// The script is larger than `ServerConfig::max_script_bytes`, so it wasn't sent.
+    PayloadTooLarge { size: usize, limit: usize },
+    /// The reply was larger than `ServerConfig::max_reply_bytes`, so zmq
+    /// dropped it without reading it into memory.
+    ReplyTooLarge { limit: usize },
+    /// A `ServerConfig` from the frontend has a setting that can't work.
+    InvalidConfig(String),
+    /// A bug on the Rust side, such as a background task panicking.
+    Internal(String),
+}
+
+impl fmt::Display for PadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PadError::Network(message) => write!(f, "network error: {}", message),
+            PadError::Timeout => write!(f, "timed out waiting for the server"),
+            PadError::Cancelled => write!(f, "request was cancelled"),
+            PadError::Decode(message) => write!(f, "could not decode reply: {}", message),
+            PadError::Io(message) => write!(f, "io error: {}", message),
+            PadError::UnknownProfile(name) => write!(f, "no server profile named {}", name),
+            PadError::PayloadTooLarge { size, limit } => write!(
+                f,
+                "script is {} bytes, more than the {} byte limit",
+                size, limit
+            ),
+            PadError::ReplyTooLarge { limit } => {
+                write!(f, "reply was more than the {} byte limit", limit)
+            }
+            PadError::InvalidConfig(message) => write!(f, "invalid server config: {}", message),
+            PadError::Internal(message) => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for PadError {}
+
+impl From<zmq::Error> for PadError {
+    fn from(e: zmq::Error) -> Self {
+        PadError::Network(e.to_string())
+    }
+}
+
+impl From<io::Error> for PadError {
+    fn from(e: io::Error) -> Self {
+        PadError::Io(e.to_string())
+    }
+}
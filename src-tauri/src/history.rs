@@ -0,0 +1,170 @@
+use crate::config;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Scripts longer than this are cut short before they're stored, so a few
+/// huge scripts can't bloat the history file.
+const MAX_STORED_SCRIPT_BYTES: usize = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub script: String,
+    /// True when `script` was cut to `MAX_STORED_SCRIPT_BYTES`.
+    #[serde(default)]
+    pub truncated: bool,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub succeeded: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueryHistory {
+    /// The most entries kept; older ones are dropped as new ones arrive.
+    pub limit: usize,
+    /// Oldest first.
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl Default for QueryHistory {
+    fn default() -> Self {
+        QueryHistory {
+            limit: 100,
+            entries: Vec::new(),
+        }
+    }
+}
+
+/// The scripts that have been run, along with the file they are persisted to.
+pub struct HistoryStore {
+    path: PathBuf,
+    history: Mutex<QueryHistory>,
+}
+
+impl HistoryStore {
+    pub fn load(path: PathBuf) -> HistoryStore {
+        let history = config::load(&path);
+        HistoryStore {
+            path,
+            history: Mutex::new(history),
+        }
+    }
+
+    pub fn record(&self, mut script: String, succeeded: bool) -> io::Result<()> {
+        let truncated = script.len() > MAX_STORED_SCRIPT_BYTES;
+        if truncated {
+            let mut end = MAX_STORED_SCRIPT_BYTES;
+            while !script.is_char_boundary(end) {
+                end -= 1;
+            }
+            script.truncate(end);
+        }
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        self.update(|history| {
+            history.entries.push(HistoryEntry {
+                script,
+                truncated,
+                timestamp_ms,
+                succeeded,
+            })
+        })
+    }
+
+    /// Up to `limit` entries, most recent first.
+    pub fn recent(&self, limit: usize) -> Vec<HistoryEntry> {
+        let history = self.history.lock().unwrap();
+        history.entries.iter().rev().take(limit).cloned().collect()
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        self.update(|history| history.entries.clear())
+    }
+
+    pub fn set_limit(&self, limit: usize) -> io::Result<()> {
+        self.update(|history| history.limit = limit)
+    }
+
+    fn update(&self, f: impl FnOnce(&mut QueryHistory)) -> io::Result<()> {
+        let mut history = self.history.lock().unwrap();
+        let mut updated = history.clone();
+        f(&mut updated);
+        let excess = updated.entries.len().saturating_sub(updated.limit);
+        updated.entries.drain(..excess);
+        config::save(&self.path, &updated)?;
+        *history = updated;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn scripts(entries: &[HistoryEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.script.as_str()).collect()
+    }
+
+    #[test]
+    fn recent_returns_newest_first() {
+        let dir = TempDir::new();
+        let history = HistoryStore::load(dir.path("recent.json"));
+        for script in ["a", "b", "c"] {
+            history.record(script.to_string(), true).unwrap();
+        }
+        assert_eq!(scripts(&history.recent(2)), ["c", "b"]);
+        assert_eq!(scripts(&history.recent(10)), ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn recording_past_the_limit_drops_the_oldest() {
+        let dir = TempDir::new();
+        let path = dir.path("capped.json");
+        let history = HistoryStore::load(path.clone());
+        history.set_limit(2).unwrap();
+        for script in ["a", "b", "c"] {
+            history.record(script.to_string(), true).unwrap();
+        }
+        assert_eq!(scripts(&HistoryStore::load(path).recent(10)), ["c", "b"]);
+    }
+
+    #[test]
+    fn lowering_the_limit_truncates() {
+        let dir = TempDir::new();
+        let path = dir.path("lowered.json");
+        let history = HistoryStore::load(path.clone());
+        for script in ["a", "b", "c"] {
+            history.record(script.to_string(), false).unwrap();
+        }
+        history.set_limit(1).unwrap();
+        assert_eq!(scripts(&HistoryStore::load(path).recent(10)), ["c"]);
+    }
+
+    #[test]
+    fn clear_persists() {
+        let dir = TempDir::new();
+        let path = dir.path("cleared.json");
+        let history = HistoryStore::load(path.clone());
+        history.record("a".to_string(), true).unwrap();
+        history.clear().unwrap();
+        assert!(HistoryStore::load(path).recent(10).is_empty());
+    }
+
+    #[test]
+    fn long_scripts_are_truncated() {
+        let dir = TempDir::new();
+        let history = HistoryStore::load(dir.path("long.json"));
+        let script = "é".repeat(MAX_STORED_SCRIPT_BYTES);
+        history.record(script, true).unwrap();
+        let entry = &history.recent(1)[0];
+        assert!(entry.truncated);
+        assert!(entry.script.len() <= MAX_STORED_SCRIPT_BYTES);
+        assert!(entry.script.chars().all(|c| c == 'é'));
+    }
+}
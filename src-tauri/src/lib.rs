@@ -1,19 +1,299 @@
+mod cancel;
+mod client;
+mod config;
+mod error;
+mod history;
+mod limit;
+#[cfg(test)]
+mod test_util;
+
+use cancel::QueryRegistry;
+use client::ServerStatus;
+use config::{ConfigStore, ProfileStore, ServerConfig, ServerProfiles};
+use error::PadError;
+use history::{HistoryEntry, HistoryStore};
+use limit::QueryLimiter;
+use serde::Serialize;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, RunEvent, State};
+
+/// How long `run_wander` calls already sent get to finish when the app quits
+/// before they are cancelled. Calls still queued are cancelled right away.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// The zmq context, active config, history and running queries, managed as
+/// one piece of Tauri state so commands can take a single `State`.
+struct Backend {
+    ctx: zmq::Context,
+    store: ConfigStore,
+    history: Arc<HistoryStore>,
+    queries: QueryRegistry,
+    limiter: QueryLimiter,
+}
+
+/// Run blocking zmq work on the blocking thread pool so a slow server
+/// doesn't tie up the async runtime's workers. A panic in `f` comes back as
+/// `PadError::Internal`.
+async fn blocking<T, F>(f: F) -> Result<T, PadError>
+where
+    F: FnOnce() -> Result<T, PadError> + Send + 'static,
+    T: Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(f).await.map_err(|e| {
+        log::error!("blocking task panicked: {}", e);
+        PadError::Internal(e.to_string())
+    })?
+}
+
+/// Payload of the `command-timing` event sent when `ServerConfig::debug_timing` is on.
+#[derive(Clone, Serialize)]
+struct CommandTiming {
+    command: &'static str,
+    duration_ms: u64,
+    /// Size of the server's reply, or 0 when there wasn't one.
+    bytes: usize,
+}
+
+fn emit_timing(app: &AppHandle, command: &'static str, start: Instant, bytes: usize) {
+    let timing = CommandTiming {
+        command,
+        duration_ms: start.elapsed().as_millis() as u64,
+        bytes,
+    };
+    if let Err(e) = app.emit("command-timing", timing) {
+        log::warn!("could not emit command-timing: {}", e);
+    }
+}
+
+/// Send a Wander script to the server. When `timeout_ms` is given it is
+/// used instead of `ServerConfig::timeout_ms` for this call only. When
+/// `token` is given the request can be stopped by passing the same token
+/// to `cancel_query`; tokens should be unique per call. The timing sent when
+/// `ServerConfig::debug_timing` is on includes time spent queued.
+#[tauri::command]
+async fn run_wander(
+    script: String,
+    timeout_ms: Option<u64>,
+    token: Option<String>,
+    app: AppHandle,
+    backend: State<'_, Backend>,
+) -> Result<String, PadError> {
+    let start = Instant::now();
+    let ctx = backend.ctx.clone();
+    let mut config = backend.store.get();
+    if let Some(timeout_ms) = timeout_ms {
+        config.timeout_ms = timeout_ms;
+    }
+    let debug_timing = config.debug_timing;
+    let timing = |bytes: usize| {
+        if debug_timing {
+            emit_timing(&app, "run_wander", start, bytes);
+        }
+    };
+    // Scripts that are never sent don't go in the history.
+    if let Err(e) = client::check_script_size(&config, &script) {
+        timing(0);
+        return Err(e);
+    }
+    let cancel = backend.queries.register(token);
+    let sent = script.clone();
+    let flag = cancel.clone();
+    let permit = match backend.limiter.acquire(&cancel).await {
+        Ok(permit) => permit,
+        Err(e) => {
+            backend.queries.finish(&cancel);
+            timing(0);
+            return Err(e);
+        }
+    };
+    backend.queries.mark_sent(&cancel);
+    let result = blocking(move || client::send_script(&ctx, &config, &sent, &flag)).await;
+    drop(permit);
+    timing(result.as_ref().map_or(0, |reply| reply.len()));
+    let history = backend.history.clone();
+    let succeeded = result.is_ok();
+    if let Err(e) = blocking(move || Ok(history.record(script, succeeded)?)).await {
+        log::warn!("could not record query history: {}", e);
+    }
+    // Only now, so quitting waits for the history entry to be written.
+    backend.queries.finish(&cancel);
+    result
+}
+
+/// Stop the `run_wander` call started with `token`. Returns false when no
+/// such call is still running.
+#[tauri::command]
+fn cancel_query(token: String, backend: State<Backend>) -> bool {
+    backend.queries.cancel(&token)
+}
+
+#[tauri::command]
+async fn check_server(
+    app: AppHandle,
+    backend: State<'_, Backend>,
+) -> Result<ServerStatus, PadError> {
+    let start = Instant::now();
+    let ctx = backend.ctx.clone();
+    let config = backend.store.get();
+    let debug_timing = config.debug_timing;
+    let status = blocking(move || client::probe(&ctx, &config)).await;
+    if debug_timing {
+        emit_timing(&app, "check_server", start, 0);
+    }
+    status
+}
+
+/// Probe the server described by `config` without making it the active one.
+/// Timing is emitted when the active config has `debug_timing` on.
+#[tauri::command]
+async fn test_config(
+    config: ServerConfig,
+    app: AppHandle,
+    backend: State<'_, Backend>,
+) -> Result<ServerStatus, PadError> {
+    let start = Instant::now();
+    let ctx = backend.ctx.clone();
+    let debug_timing = backend.store.get().debug_timing;
+    let status = blocking(move || client::probe(&ctx, &config)).await;
+    if debug_timing {
+        emit_timing(&app, "test_config", start, 0);
+    }
+    status
+}
+
+#[tauri::command]
+fn get_server_config(backend: State<Backend>) -> ServerConfig {
+    backend.store.get()
+}
+
+/// Replace the active config. It no longer comes from a saved profile, so
+/// the active profile is cleared.
+#[tauri::command]
+fn set_server_config(
+    config: ServerConfig,
+    backend: State<Backend>,
+    profiles: State<ProfileStore>,
+) -> Result<(), PadError> {
+    config.validate()?;
+    let limit = config.max_concurrent_queries;
+    backend.store.set(config)?;
+    backend.limiter.resize(limit);
+    profiles.set_active(None)
+}
+
+/// The last `limit` scripts that were run, most recent first.
+#[tauri::command]
+fn query_history(limit: usize, backend: State<Backend>) -> Vec<HistoryEntry> {
+    backend.history.recent(limit)
+}
+
+#[tauri::command(async)]
+fn clear_query_history(backend: State<Backend>) -> Result<(), PadError> {
+    Ok(backend.history.clear()?)
+}
+
+/// Change how many entries the query history keeps.
 #[tauri::command(async)]
-fn run_wander(script: String) -> String {
-    let ctx = zmq::Context::new();
-    let socket = ctx.socket(zmq::REQ).unwrap();
-    socket.connect("tcp://127.0.0.1:4200").unwrap();
-    socket.send(&script, 0).unwrap();
-    socket.recv_string(0).unwrap().unwrap()
+fn set_query_history_limit(limit: usize, backend: State<Backend>) -> Result<(), PadError> {
+    Ok(backend.history.set_limit(limit)?)
+}
+
+/// The saved profiles, along with the name of the active one if any.
+#[tauri::command]
+fn list_profiles(profiles: State<ProfileStore>) -> ServerProfiles {
+    profiles.list()
+}
+
+/// Save `config` as the profile called `name`. When that is the active
+/// profile, `config` becomes the active server config as well.
+#[tauri::command]
+fn add_profile(
+    name: String,
+    config: ServerConfig,
+    profiles: State<ProfileStore>,
+    backend: State<Backend>,
+) -> Result<(), PadError> {
+    config.validate()?;
+    let limit = config.max_concurrent_queries;
+    if profiles.add(name, config.clone())? {
+        backend.store.set(config)?;
+        backend.limiter.resize(limit);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn remove_profile(name: String, profiles: State<ProfileStore>) -> Result<(), PadError> {
+    profiles.remove(&name)
+}
+
+/// Make the profile called `name` the active server config.
+#[tauri::command]
+fn activate_profile(
+    name: String,
+    profiles: State<ProfileStore>,
+    backend: State<Backend>,
+) -> Result<(), PadError> {
+    let config = profiles.get(&name)?;
+    let limit = config.max_concurrent_queries;
+    backend.store.set(config)?;
+    backend.limiter.resize(limit);
+    profiles.set_active(Some(name))
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    env_logger::init();
     tauri::Builder::default()
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_shell::init())
-        .invoke_handler(tauri::generate_handler![run_wander])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .setup(|app| {
+            let dir = app.path().app_config_dir()?;
+            let store = ConfigStore::load(dir.join("server.json"));
+            app.manage(Backend {
+                ctx: zmq::Context::new(),
+                limiter: QueryLimiter::new(store.get().max_concurrent_queries),
+                store,
+                history: Arc::new(HistoryStore::load(dir.join("history.json"))),
+                queries: QueryRegistry::default(),
+            });
+            app.manage(ProfileStore::load(dir.join("profiles.json")));
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            run_wander,
+            cancel_query,
+            check_server,
+            test_config,
+            get_server_config,
+            set_server_config,
+            query_history,
+            clear_query_history,
+            set_query_history_limit,
+            list_profiles,
+            add_profile,
+            remove_profile,
+            activate_profile
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Hold off exiting while queries are still running, and wait
+            // for them off the main thread so the UI doesn't freeze.
+            if let RunEvent::ExitRequested { code, api, .. } = event {
+                let backend = app.state::<Backend>();
+                if backend.queries.is_idle() || !backend.queries.begin_shutdown() {
+                    return;
+                }
+                api.prevent_exit();
+                let app = app.clone();
+                thread::spawn(move || {
+                    app.state::<Backend>().queries.shutdown(SHUTDOWN_GRACE);
+                    app.exit(code.unwrap_or(0));
+                });
+            }
+        });
 }
@@ -0,0 +1,159 @@
+use crate::cancel::CancelFlag;
+use crate::error::PadError;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Caps how many `run_wander` calls talk to the server at once. Calls over
+/// the limit wait for a free slot rather than failing.
+pub struct QueryLimiter {
+    semaphore: Arc<Semaphore>,
+    state: Arc<Mutex<LimitState>>,
+}
+
+struct LimitState {
+    limit: usize,
+    /// Slots still to be removed after lowering the limit while they were in
+    /// use. Each one is taken away as its permit is released.
+    owed: usize,
+}
+
+/// A held slot, given back to the limiter when dropped.
+pub struct QueryPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    state: Arc<Mutex<LimitState>>,
+}
+
+impl Drop for QueryPermit {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(permit) = self.permit.take() {
+            if state.owed > 0 {
+                state.owed -= 1;
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl QueryLimiter {
+    pub fn new(limit: usize) -> QueryLimiter {
+        let limit = limit.max(1);
+        QueryLimiter {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            state: Arc::new(Mutex::new(LimitState { limit, owed: 0 })),
+        }
+    }
+
+    /// Wait for a free slot, giving up with `PadError::Cancelled` as soon as
+    /// `cancel` is set.
+    pub async fn acquire(&self, cancel: &CancelFlag) -> Result<QueryPermit, PadError> {
+        let permit = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return Err(PadError::Cancelled),
+            permit = self.semaphore.clone().acquire_owned() => permit,
+        };
+        Ok(QueryPermit {
+            permit: Some(permit.expect("query semaphore is never closed")),
+            state: self.state.clone(),
+        })
+    }
+
+    /// Apply a new limit, including to calls already waiting. Raising it lets
+    /// waiting calls start right away. Lowering it never interrupts a call
+    /// holding a slot; the extra slots go away as those calls finish.
+    pub fn resize(&self, limit: usize) {
+        let limit = limit.max(1);
+        let mut state = self.state.lock().unwrap();
+        if limit > state.limit {
+            let extra = limit - state.limit;
+            let repaid = extra.min(state.owed);
+            state.owed -= repaid;
+            self.semaphore.add_permits(extra - repaid);
+        } else if limit < state.limit {
+            let fewer = state.limit - limit;
+            let forgotten = self.semaphore.forget_permits(fewer);
+            state.owed += fewer - forgotten;
+        }
+        state.limit = limit;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Start `count` calls that each take a slot and hold it in `held`.
+    fn queue(limiter: &Arc<QueryLimiter>, held: &Arc<Mutex<Vec<QueryPermit>>>, count: usize) {
+        for _ in 0..count {
+            let limiter = limiter.clone();
+            let held = held.clone();
+            tokio::spawn(async move {
+                let permit = limiter.acquire(&CancelFlag::default()).await.unwrap();
+                held.lock().unwrap().push(permit);
+            });
+        }
+    }
+
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    fn count(held: &Arc<Mutex<Vec<QueryPermit>>>) -> usize {
+        held.lock().unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn raising_the_limit_starts_queued_calls() {
+        let limiter = Arc::new(QueryLimiter::new(1));
+        let held = Arc::new(Mutex::new(Vec::new()));
+        queue(&limiter, &held, 4);
+        settle().await;
+        assert_eq!(count(&held), 1);
+        limiter.resize(4);
+        settle().await;
+        assert_eq!(count(&held), 4);
+    }
+
+    #[tokio::test]
+    async fn lowering_the_limit_applies_to_queued_calls() {
+        let limiter = Arc::new(QueryLimiter::new(4));
+        let held = Arc::new(Mutex::new(Vec::new()));
+        queue(&limiter, &held, 7);
+        settle().await;
+        assert_eq!(count(&held), 4);
+        limiter.resize(1);
+        held.lock().unwrap().clear();
+        settle().await;
+        assert_eq!(count(&held), 1);
+        held.lock().unwrap().clear();
+        settle().await;
+        assert_eq!(count(&held), 1);
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_queued_call_stops_its_wait() {
+        let limiter = QueryLimiter::new(1);
+        let _held = limiter.acquire(&CancelFlag::default()).await.unwrap();
+        let cancel = CancelFlag::default();
+        let canceller = cancel.clone();
+        tokio::spawn(async move { canceller.cancel() });
+        let result = limiter.acquire(&cancel).await;
+        assert!(matches!(result, Err(PadError::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn raising_after_lowering_repays_owed_slots() {
+        let limiter = Arc::new(QueryLimiter::new(2));
+        let held = Arc::new(Mutex::new(Vec::new()));
+        queue(&limiter, &held, 4);
+        settle().await;
+        assert_eq!(count(&held), 2);
+        limiter.resize(1);
+        limiter.resize(2);
+        held.lock().unwrap().clear();
+        settle().await;
+        assert_eq!(count(&held), 2);
+    }
+}
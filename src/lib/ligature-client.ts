@@ -1,5 +1,94 @@
 import { invoke } from '@tauri-apps/api/core';
 
-export async function runWander(script: string): Promise<string> {
-    return await invoke("run_wander", {script} )
+export type ServerConfig = {
+    host: string,
+    port: number,
+    scheme: string,
+    timeout_ms: number,
+    max_concurrent_queries: number,
+    max_script_bytes: number,
+    max_reply_bytes: number,
+    debug_timing: boolean,
+}
+
+export type ServerProfiles = {
+    active: string | null,
+    profiles: Record<string, ServerConfig>,
+}
+
+export type CommandTiming = {
+    command: string,
+    duration_ms: number,
+    bytes: number,
+}
+
+export type ServerStatus = {
+    reachable: boolean,
+    version: string | null,
+    latency_ms: number,
+}
+
+export type HistoryEntry = {
+    script: string,
+    truncated: boolean,
+    timestamp_ms: number,
+    succeeded: boolean,
+}
+
+export type PadError =
+    | { kind: "Network" | "Decode" | "Io" | "UnknownProfile" | "InvalidConfig" | "Internal", message: string }
+    | { kind: "Timeout" | "Cancelled" }
+    | { kind: "PayloadTooLarge", message: { size: number, limit: number } }
+    | { kind: "ReplyTooLarge", message: { limit: number } }
+
+export async function runWander(script: string, timeoutMs?: number, token?: string): Promise<string> {
+    return await invoke("run_wander", {script, timeoutMs, token} )
+}
+
+export async function cancelQuery(token: string): Promise<boolean> {
+    return await invoke("cancel_query", {token} )
+}
+
+export async function checkServer(): Promise<ServerStatus> {
+    return await invoke("check_server")
+}
+
+export async function testConfig(config: ServerConfig): Promise<ServerStatus> {
+    return await invoke("test_config", {config} )
+}
+
+export async function getServerConfig(): Promise<ServerConfig> {
+    return await invoke("get_server_config")
+}
+
+export async function setServerConfig(config: ServerConfig): Promise<void> {
+    return await invoke("set_server_config", {config} )
+}
+
+export async function queryHistory(limit: number): Promise<HistoryEntry[]> {
+    return await invoke("query_history", {limit} )
+}
+
+export async function clearQueryHistory(): Promise<void> {
+    return await invoke("clear_query_history")
+}
+
+export async function setQueryHistoryLimit(limit: number): Promise<void> {
+    return await invoke("set_query_history_limit", {limit} )
+}
+
+export async function listProfiles(): Promise<ServerProfiles> {
+    return await invoke("list_profiles")
+}
+
+export async function addProfile(name: string, config: ServerConfig): Promise<void> {
+    return await invoke("add_profile", {name, config} )
+}
+
+export async function removeProfile(name: string): Promise<void> {
+    return await invoke("remove_profile", {name} )
+}
+
+export async function activateProfile(name: string): Promise<void> {
+    return await invoke("activate_profile", {name} )
 }